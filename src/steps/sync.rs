@@ -1,29 +1,288 @@
 //! Synchronizes changes from the physics world to Bevy [`Transform`]s.
 
+use std::collections::{HashMap, HashSet};
+
 use crate::{prelude::*, XpbdSchedule};
-use bevy::prelude::*;
+use bevy::{
+    ecs::{component::Tick, schedule::apply_system_buffers, system::SystemChangeTick},
+    prelude::*,
+};
+
+/// The physics tick at which the most recently completed physics step finished.
+///
+/// Waking systems compare this against `Ref::last_changed()` on the components that can
+/// wake a sleeping body, so that a change made anywhere in the *previous* step or frame
+/// (e.g. an `ExternalForce` applied from a gameplay system) is still seen, unlike a
+/// `Changed<T>` filter whose "changed since last run" window resets every time the
+/// wake system itself runs.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct LastPhysicsTick(pub Tick);
+
+/// Identifies the connected-body island an entity currently belongs to.
+///
+/// Bodies connected through active contacts or joints are grouped into the same island,
+/// identified by one of its members (the union-find root computed by [`build_islands`]).
+/// Sleeping is decided for the island as a whole: every member must be below the sleeping
+/// thresholds for longer than `DeactivationTime` before any of them sleeps, and waking one
+/// member wakes all of them in the same step.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PhysicsIsland(pub Entity);
+
+/// The [`Pos`] a body had at the start of the current physics step.
+///
+/// Only populated for bodies with [`TransformInterpolation`]; used by [`interpolate_transforms`]
+/// as the interpolation source pose.
+#[derive(Component, Clone, Copy, Debug, Deref, DerefMut)]
+pub struct PreviousPos(pub Pos);
+
+/// The [`Rot`] a body had at the start of the current physics step.
+///
+/// Only populated for bodies with [`TransformInterpolation`]; used by [`interpolate_transforms`]
+/// as the interpolation source pose.
+#[derive(Component, Clone, Copy, Debug, Deref, DerefMut)]
+pub struct PreviousRot(pub Rot);
+
+/// Marker that opts a body into interpolating its rendered [`Transform`] between physics
+/// steps, instead of snapping straight to the latest [`Pos`]/[`Rot`].
+///
+/// This smooths out motion when the physics schedule runs at a fixed timestep slower
+/// than the render framerate, and avoids visible snapping between discrete solver
+/// positions at high framerates.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct TransformInterpolation;
+
+/// Skips interpolation for one physics step, so a body that was just teleported snaps
+/// straight to its new pose instead of sliding there from its previous one.
+///
+/// Removed automatically once it has been consumed.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct SkipInterpolation;
+
+/// Marks this frame's `Transform` change as a teleport rather than continuous scripted
+/// motion, so [`sync_transforms_to_physics`] applies the new pose without deriving a
+/// velocity from the jump (a kinematic body teleported across the level shouldn't launch
+/// whatever it lands on). Removed automatically once consumed.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Teleport;
 
 /// Synchronizes changes from the physics world to Bevy [`Transform`]s.
 pub struct SyncPlugin;
 
 impl Plugin for SyncPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
+        app.init_resource::<LastPhysicsTick>();
         app.get_schedule_mut(XpbdSchedule)
             .expect("add xpbd schedule first")
-            .add_system(sync_transforms.in_set(PhysicsSet::Sync))
             .add_systems(
                 (
-                    activate_sleeping,
-                    deactivate_sleeping,
-                    gravity_deactivate_sleeping,
+                    // Islands are built first so that waking or writing back a body this
+                    // step can wake the rest of its island in the same step, instead of
+                    // one step later. They're still built from the previous step's
+                    // contacts, since narrow-phase collision detection hasn't run yet for
+                    // this step — contact-driven island membership necessarily lags
+                    // collision by one step, but that's now the only lag left.
+                    build_islands,
+                    // `build_islands` inserts `PhysicsIsland` through `Commands`; flush it
+                    // so the waking systems below see this step's islands.
+                    apply_system_buffers,
+                    sync_transforms_to_physics,
+                    store_previous_pos_rot,
+                    wake_on_external_change,
+                    // `sync_transforms_to_physics` and `wake_on_external_change` both
+                    // remove `Sleeping` through `Commands`. Flush those removals here,
+                    // at the end of `Prepare`, so the solver sees an awakened body as
+                    // already awake and simulates it this step instead of one step late.
+                    apply_system_buffers,
                 )
+                    .chain()
+                    .in_set(PhysicsSet::Prepare),
+            )
+            .add_systems(
+                (sync_transforms, clear_skip_interpolation)
+                    .chain()
+                    .in_set(PhysicsSet::Sync),
+            )
+            .add_systems(
+                (activate_sleeping, gravity_deactivate_sleeping)
                     .chain()
                     .in_set(PhysicsSet::Sync),
+            )
+            .add_system(
+                update_last_physics_tick
+                    .in_set(PhysicsSet::Sync)
+                    .after(activate_sleeping)
+                    .after(sync_transforms),
             );
+
+        // Runs at the render rate rather than the physics schedule's fixed rate, so
+        // interpolated bodies get a fresh, smoothly advancing pose every frame.
+        app.add_systems(Update, interpolate_transforms);
+    }
+}
+
+/// Derives a velocity from how far a value moved over `dt`, or zero if the move was a
+/// teleport (a jump has no physically-meaningful velocity) or `dt` isn't positive (which
+/// would otherwise divide by zero).
+fn velocity_from_delta<T>(delta: T, dt: f32, teleported: bool) -> T
+where
+    T: std::ops::Div<f64, Output = T> + Default,
+{
+    if teleported || dt <= 0.0 {
+        T::default()
+    } else {
+        delta / dt as f64
+    }
+}
+
+/// Derives an angular velocity from the rotation a body moved through over `dt`, expressed
+/// as the quaternion `delta = new_rot * old_rot.inverse()`, or zero if the move was a
+/// teleport or `dt` isn't positive.
+///
+/// Uses the small-angle approximation `ω ≈ 2 * delta.xyz() / dt`, which is accurate for the
+/// small rotation a single physics step typically covers.
+#[cfg(feature = "3d")]
+fn angular_velocity_from_rotation_delta(delta: Quaternion, dt: f32, teleported: bool) -> Vector {
+    if teleported || dt <= 0.0 {
+        Vector::ZERO
+    } else {
+        2.0 * delta.xyz() / dt as f64
+    }
+}
+
+/// Writes user-set [`Transform`]s back into [`Pos`]/[`Rot`], so teleporting a body,
+/// scripting a kinematic platform, or placing a spawned entity by setting its `Transform`
+/// behaves the way it does for every other Bevy component, instead of being silently
+/// overwritten by the next [`sync_transforms`].
+///
+/// Guarded by [`LastPhysicsTick`] rather than `Changed<Transform>`, so it doesn't react to
+/// the write `sync_transforms` itself made at the end of the previous step.
+///
+/// Excludes [`TransformInterpolation`] bodies entirely: `interpolate_transforms` writes
+/// their `Transform` every render frame to an in-between pose, which would otherwise look
+/// like a fresh user write here and feed the interpolated pose back into `Pos`/`Rot`,
+/// corrupting the physics state (and waking the body every step, since its `Transform`
+/// would then always look freshly changed).
+fn sync_transforms_to_physics(
+    mut commands: Commands,
+    last_tick: Res<LastPhysicsTick>,
+    ticks: SystemChangeTick,
+    dt: Res<DeltaTime>,
+    mut bodies: Query<
+        (
+            Entity,
+            Ref<Transform>,
+            &mut Pos,
+            &mut Rot,
+            Option<&RigidBody>,
+            Option<&mut LinVel>,
+            Option<&mut AngVel>,
+            Option<&PhysicsIsland>,
+            Has<Teleport>,
+        ),
+        Without<TransformInterpolation>,
+    >,
+    mut sleeping: Query<(Entity, &PhysicsIsland, &mut TimeSleeping), With<Sleeping>>,
+) {
+    for (entity, transform, mut pos, mut rot, rb, lin_vel, ang_vel, island, is_teleport) in
+        &mut bodies
+    {
+        if !transform
+            .last_changed()
+            .is_newer_than(last_tick.0, ticks.this_run())
+        {
+            continue;
+        }
+
+        let previous_pos = pos.0;
+        let previous_rot = *rot;
+
+        #[cfg(feature = "2d")]
+        {
+            pos.0 = transform.translation.truncate().as_dvec2();
+            *rot = Rot::from(transform.rotation);
+        }
+        #[cfg(feature = "3d")]
+        {
+            pos.0 = transform.translation.as_dvec3();
+            rot.0 = Quaternion::from(transform.rotation);
+        }
+
+        if rb.is_some_and(RigidBody::is_kinematic) {
+            if let Some(mut lin_vel) = lin_vel {
+                lin_vel.0 = velocity_from_delta(pos.0 - previous_pos, dt.0, is_teleport);
+            }
+            if let Some(mut ang_vel) = ang_vel {
+                #[cfg(feature = "2d")]
+                {
+                    ang_vel.0 = velocity_from_delta(
+                        rot.as_radians() - previous_rot.as_radians(),
+                        dt.0,
+                        is_teleport,
+                    );
+                }
+                #[cfg(feature = "3d")]
+                {
+                    let delta = rot.0 * previous_rot.0.inverse();
+                    ang_vel.0 = angular_velocity_from_rotation_delta(delta, dt.0, is_teleport);
+                }
+            }
+        }
+
+        if is_teleport {
+            commands.entity(entity).insert(SkipInterpolation);
+            commands.entity(entity).remove::<Teleport>();
+        }
+
+        // The `Without<TransformInterpolation>` filter above matters here too, not just
+        // for the write-back: without it, every interpolated body's `Transform` would
+        // look freshly changed on every step (it's rewritten each render frame by
+        // `interpolate_transforms`), waking it and keeping it awake permanently.
+        commands.entity(entity).remove::<Sleeping>();
+
+        if let Some(island) = island {
+            for (other, other_island, mut time_sleeping) in &mut sleeping {
+                if other_island.0 == island.0 {
+                    commands.entity(other).remove::<Sleeping>();
+                    time_sleeping.0 = 0.0;
+                }
+            }
+        }
+    }
+}
+
+/// Captures each interpolated body's pose at the start of the step, before the solver
+/// advances it, so [`interpolate_transforms`] has a source pose to interpolate from.
+fn store_previous_pos_rot(
+    mut commands: Commands,
+    bodies: Query<(Entity, &Pos, &Rot), With<TransformInterpolation>>,
+) {
+    for (entity, pos, rot) in &bodies {
+        commands
+            .entity(entity)
+            .insert((PreviousPos(*pos), PreviousRot(*rot)));
+    }
+}
+
+/// How far between the previous and current physics step's pose to render this frame,
+/// derived from the fixed-timestep accumulator's leftover time, clamped to `[0, 1]`.
+///
+/// Returns `Scalar` rather than `f32` so it can feed [`Pos`]/[`Rot`]'s double-precision
+/// `lerp` directly; callers that need it for `Transform`'s single-precision `Quat::slerp`
+/// narrow it to `f32` at the call site instead.
+fn interpolation_alpha(fixed_time: &FixedTime) -> Scalar {
+    let period = fixed_time.period.as_secs_f64();
+    if period > 0.0 {
+        (fixed_time.accumulated().as_secs_f64() / period).clamp(0.0, 1.0)
+    } else {
+        0.0
     }
 }
 
 /// Copies [`Pos`] and [`Rot`] values from the physics world to Bevy [`Transform`]s.
+///
+/// This runs once per physics step, at the physics schedule's fixed rate. Bodies with
+/// [`TransformInterpolation`] get this raw pose overwritten every render frame by
+/// [`interpolate_transforms`], which runs at the variable render rate instead.
 #[cfg(feature = "2d")]
 fn sync_transforms(mut bodies: Query<(&mut Transform, &Pos, &Rot)>) {
     for (mut transform, pos, rot) in &mut bodies {
@@ -35,6 +294,10 @@ fn sync_transforms(mut bodies: Query<(&mut Transform, &Pos, &Rot)>) {
 }
 
 /// Copies [`Pos`] and [`Rot`] values from the physics world to Bevy's [`Transform`]s.
+///
+/// This runs once per physics step, at the physics schedule's fixed rate. Bodies with
+/// [`TransformInterpolation`] get this raw pose overwritten every render frame by
+/// [`interpolate_transforms`], which runs at the variable render rate instead.
 #[cfg(feature = "3d")]
 fn sync_transforms(mut bodies: Query<(&mut Transform, &Pos, &Rot)>) {
     for (mut transform, pos, rot) in &mut bodies {
@@ -43,6 +306,185 @@ fn sync_transforms(mut bodies: Query<(&mut Transform, &Pos, &Rot)>) {
     }
 }
 
+/// Interpolates [`Transform`] between the previous and current physics step's pose, for
+/// bodies with [`TransformInterpolation`].
+///
+/// Unlike [`sync_transforms`], this runs in the variable-rate render (`Update`) schedule
+/// rather than the fixed-rate physics schedule, so it writes a new, smoothly advancing
+/// pose every render frame even when the physics schedule runs less often than the
+/// renderer — which is the whole point of interpolation. Running it at the physics
+/// schedule's own fixed rate would produce exactly one (already-settled) pose per step and
+/// wouldn't remove any stutter.
+#[cfg(feature = "2d")]
+fn interpolate_transforms(
+    fixed_time: Res<FixedTime>,
+    mut bodies: Query<
+        (&mut Transform, &Pos, &Rot, &PreviousPos, &PreviousRot),
+        (With<TransformInterpolation>, Without<SkipInterpolation>),
+    >,
+) {
+    let alpha = interpolation_alpha(&fixed_time);
+
+    for (mut transform, pos, rot, prev_pos, prev_rot) in &mut bodies {
+        let previous = prev_pos.extend(0.0);
+        let current = pos.extend(0.0);
+        transform.translation = previous.lerp(current, alpha).as_vec3_f32();
+
+        let previous_quat: Quaternion = prev_rot.0.into();
+        let current_quat: Quaternion = (*rot).into();
+        transform.rotation = previous_quat
+            .as_quat_f32()
+            .slerp(current_quat.as_quat_f32(), alpha as f32);
+    }
+}
+
+/// Interpolates [`Transform`] between the previous and current physics step's pose, for
+/// bodies with [`TransformInterpolation`].
+///
+/// Unlike [`sync_transforms`], this runs in the variable-rate render (`Update`) schedule
+/// rather than the fixed-rate physics schedule, so it writes a new, smoothly advancing
+/// pose every render frame even when the physics schedule runs less often than the
+/// renderer — which is the whole point of interpolation. Running it at the physics
+/// schedule's own fixed rate would produce exactly one (already-settled) pose per step and
+/// wouldn't remove any stutter.
+#[cfg(feature = "3d")]
+fn interpolate_transforms(
+    fixed_time: Res<FixedTime>,
+    mut bodies: Query<
+        (&mut Transform, &Pos, &Rot, &PreviousPos, &PreviousRot),
+        (With<TransformInterpolation>, Without<SkipInterpolation>),
+    >,
+) {
+    let alpha = interpolation_alpha(&fixed_time);
+
+    for (mut transform, pos, rot, prev_pos, prev_rot) in &mut bodies {
+        transform.translation = prev_pos.0.lerp(pos.0, alpha).as_vec3_f32();
+        transform.rotation = prev_rot
+            .0
+            .as_quat_f32()
+            .slerp(rot.0.as_quat_f32(), alpha as f32);
+    }
+}
+
+/// Removes [`SkipInterpolation`] once it has suppressed one step of interpolation.
+fn clear_skip_interpolation(
+    mut commands: Commands,
+    bodies: Query<Entity, With<SkipInterpolation>>,
+) {
+    for entity in &bodies {
+        commands.entity(entity).remove::<SkipInterpolation>();
+    }
+}
+
+fn find(parents: &mut HashMap<Entity, Entity>, mut entity: Entity) -> Entity {
+    while parents[&entity] != entity {
+        let grandparent = parents[&parents[&entity]];
+        parents.insert(entity, grandparent);
+        entity = grandparent;
+    }
+    entity
+}
+
+fn union(parents: &mut HashMap<Entity, Entity>, a: Entity, b: Entity) {
+    // Entities missing from `parents` are static or kinematic bodies, which are island
+    // boundaries rather than bridges between islands.
+    if !parents.contains_key(&a) || !parents.contains_key(&b) {
+        return;
+    }
+    let root_a = find(parents, a);
+    let root_b = find(parents, b);
+    if root_a != root_b {
+        parents.insert(root_a, root_b);
+    }
+}
+
+/// Groups `dynamic_bodies` into islands by merging every pair connected by an `edges`
+/// entry (an active contact or joint), via union-find. Bodies not listed in
+/// `dynamic_bodies` (static or kinematic bodies) act as island boundaries: an edge
+/// touching one never bridges the islands on either side of it.
+///
+/// Returns each dynamic body's island, identified by one of its members (the union-find
+/// root).
+fn union_find_islands(
+    dynamic_bodies: impl Iterator<Item = Entity>,
+    edges: impl Iterator<Item = (Entity, Entity)>,
+) -> HashMap<Entity, Entity> {
+    let mut parents: HashMap<Entity, Entity> =
+        dynamic_bodies.map(|entity| (entity, entity)).collect();
+
+    for (a, b) in edges {
+        union(&mut parents, a, b);
+    }
+
+    for entity in parents.keys().copied().collect::<Vec<_>>() {
+        let root = find(&mut parents, entity);
+        parents.insert(entity, root);
+    }
+
+    parents
+}
+
+/// Builds this step's [`PhysicsIsland`]s from the current contact and joint graph.
+///
+/// Every dynamic body starts out as its own island. Each active contact or joint between
+/// two dynamic bodies merges their islands via union-find; static and kinematic bodies
+/// act as island boundaries and never cause two islands to merge. An island is identified
+/// by one of its members, the union-find root.
+///
+/// `Query<&dyn Joint>` relies on every joint type being registered as `#[bevy_trait_query::
+/// queryable]` where `Joint` is defined; that's already required for anything in this crate
+/// that iterates joints generically.
+fn build_islands(
+    mut commands: Commands,
+    bodies: Query<(Entity, &RigidBody)>,
+    colliding: Query<(Entity, &CollidingEntities)>,
+    joints: Query<&dyn Joint>,
+) {
+    let dynamic_bodies = bodies
+        .iter()
+        .filter(|(_, rb)| rb.is_dynamic())
+        .map(|(entity, _)| entity);
+
+    let mut edges = Vec::new();
+    for (entity, colliding_entities) in &colliding {
+        for other in colliding_entities.iter() {
+            edges.push((entity, *other));
+        }
+    }
+    for joint in &joints {
+        let [entity1, entity2] = joint.entities();
+        edges.push((entity1, entity2));
+    }
+
+    for (entity, island) in union_find_islands(dynamic_bodies, edges.into_iter()) {
+        commands.entity(entity).insert(PhysicsIsland(island));
+    }
+}
+
+/// Picks the islands that qualify to sleep this step: every member of
+/// `island_min_time_sleeping` whose accumulated still time exceeds `deactivation_time`,
+/// minus any island in `moving_sleeping_disabled_islands` — a `SleepingDisabled` member
+/// that is still actually moving should keep its island awake, the same as any other
+/// moving member would, even though it can never itself sleep or accumulate
+/// `TimeSleeping`. A `SleepingDisabled` member that's at rest imposes no such exclusion.
+fn islands_past_deactivation_time(
+    island_min_time_sleeping: HashMap<Entity, f32>,
+    deactivation_time: f32,
+    moving_sleeping_disabled_islands: impl Iterator<Item = Entity>,
+) -> HashSet<Entity> {
+    let mut sleeping_islands: HashSet<Entity> = island_min_time_sleeping
+        .into_iter()
+        .filter(|(_, min_time_sleeping)| *min_time_sleeping > deactivation_time)
+        .map(|(island, _)| island)
+        .collect();
+
+    for island in moving_sleeping_disabled_islands {
+        sleeping_islands.remove(&island);
+    }
+
+    sleeping_islands
+}
+
 fn activate_sleeping(
     mut commands: Commands,
     mut bodies: Query<
@@ -52,14 +494,28 @@ fn activate_sleeping(
             &mut LinVel,
             &mut AngVel,
             &mut TimeSleeping,
+            Option<&PhysicsIsland>,
         ),
         (Without<Sleeping>, Without<SleepingDisabled>),
     >,
+    // Bodies with sleeping disabled never themselves sleep, but a still-moving one should
+    // keep its island awake too, and `SleepingDisabled` bodies are excluded from the query
+    // above so they'd otherwise never hold their island's minimum time sleeping down. A
+    // `SleepingDisabled` body at rest, though, imposes no such exclusion.
+    sleeping_disabled: Query<(&PhysicsIsland, &LinVel, &AngVel), With<SleepingDisabled>>,
     deactivation_time: Res<DeactivationTime>,
     sleep_threshold: Res<SleepingThreshold>,
     dt: Res<DeltaTime>,
 ) {
-    for (entity, rb, mut lin_vel, mut ang_vel, mut time_sleeping) in &mut bodies {
+    // Negative thresholds indicate that sleeping is disabled.
+    let lin_sleeping_threshold_sq = sleep_threshold.linear * sleep_threshold.linear.abs();
+    let ang_sleeping_threshold_sq = sleep_threshold.angular * sleep_threshold.angular.abs();
+
+    // The minimum time sleeping across each island's members: an island may only fall
+    // asleep once *every* member has qualified for longer than `DeactivationTime`.
+    let mut island_min_time_sleeping: HashMap<Entity, f32> = HashMap::new();
+
+    for (entity, rb, lin_vel, ang_vel, mut time_sleeping, island) in &mut bodies {
         // Only dynamic bodies can sleep.
         if !rb.is_dynamic() {
             continue;
@@ -72,10 +528,6 @@ fn activate_sleeping(
         #[cfg(feature = "3d")]
         let ang_vel_sq = ang_vel.dot(ang_vel.0);
 
-        // Negative thresholds indicate that sleeping is disabled.
-        let lin_sleeping_threshold_sq = sleep_threshold.linear * sleep_threshold.linear.abs();
-        let ang_sleeping_threshold_sq = sleep_threshold.angular * sleep_threshold.angular.abs();
-
         // If linear and angular velocity are below the sleeping threshold,
         // add delta time to the time sleeping, i.e. the time that the body has remained still.
         if lin_vel_sq < lin_sleeping_threshold_sq && ang_vel_sq < ang_sleeping_threshold_sq {
@@ -84,8 +536,47 @@ fn activate_sleeping(
             time_sleeping.0 = 0.0;
         }
 
-        // If the body has been still for long enough, set it to sleep and reset velocities.
-        if time_sleeping.0 > deactivation_time.0 {
+        let island_root = island.map_or(entity, |island| island.0);
+        island_min_time_sleeping
+            .entry(island_root)
+            .and_modify(|min| *min = min.min(time_sleeping.0))
+            .or_insert(time_sleeping.0);
+    }
+
+    let moving_sleeping_disabled_islands =
+        sleeping_disabled
+            .iter()
+            .filter_map(|(island, lin_vel, ang_vel)| {
+                let lin_vel_sq = lin_vel.length_squared();
+                #[cfg(feature = "2d")]
+                let ang_vel_sq = ang_vel.powi(2);
+                #[cfg(feature = "3d")]
+                let ang_vel_sq = ang_vel.dot(ang_vel.0);
+
+                let is_moving = lin_vel_sq >= lin_sleeping_threshold_sq
+                    || ang_vel_sq >= ang_sleeping_threshold_sq;
+                is_moving.then_some(island.0)
+            });
+
+    let sleeping_islands = islands_past_deactivation_time(
+        island_min_time_sleeping,
+        deactivation_time.0,
+        moving_sleeping_disabled_islands,
+    );
+
+    if sleeping_islands.is_empty() {
+        return;
+    }
+
+    // Put every body whose island qualified to sleep, not just the ones that individually
+    // crossed the threshold first, so a whole resting stack sleeps in the same step.
+    for (entity, rb, mut lin_vel, mut ang_vel, _, island) in &mut bodies {
+        if !rb.is_dynamic() {
+            continue;
+        }
+
+        let island_root = island.map_or(entity, |island| island.0);
+        if sleeping_islands.contains(&island_root) {
             commands.entity(entity).insert(Sleeping);
             *lin_vel = LinVel::ZERO;
             *ang_vel = AngVel::ZERO;
@@ -93,23 +584,71 @@ fn activate_sleeping(
     }
 }
 
-type BodyActivatedFilter = Or<(
-    Changed<LinVel>,
-    Changed<AngVel>,
-    Changed<ExternalForce>,
-    Changed<ExternalTorque>,
-)>;
-
-fn deactivate_sleeping(
+/// Wakes sleeping bodies whose velocity or external forces changed since the last physics
+/// step, so that e.g. an impulse applied to a sleeping body takes effect the same step
+/// instead of being discarded before the body ever wakes up to integrate it.
+///
+/// Waking any single member of an island wakes the whole island in the same step, since a
+/// body resting on something that just started moving can't stay asleep.
+///
+/// Runs at the start of the schedule, before the solver, so a body that wakes here is
+/// actually simulated this step rather than one step later.
+fn wake_on_external_change(
     mut commands: Commands,
-    mut bodies: Query<(Entity, &mut TimeSleeping), (With<Sleeping>, BodyActivatedFilter)>,
+    last_tick: Res<LastPhysicsTick>,
+    ticks: SystemChangeTick,
+    bodies: Query<
+        (
+            Entity,
+            Option<&PhysicsIsland>,
+            Ref<LinVel>,
+            Ref<AngVel>,
+            Ref<ExternalForce>,
+            Ref<ExternalTorque>,
+        ),
+        With<Sleeping>,
+    >,
 ) {
-    for (entity, mut time_sleeping) in &mut bodies {
-        commands.entity(entity).remove::<Sleeping>();
-        time_sleeping.0 = 0.0;
+    let mut woken_entities = HashSet::new();
+    let mut woken_islands = HashSet::new();
+
+    for (entity, island, lin_vel, ang_vel, force, torque) in &bodies {
+        let woken = [
+            lin_vel.last_changed(),
+            ang_vel.last_changed(),
+            force.last_changed(),
+            torque.last_changed(),
+        ]
+        .into_iter()
+        .any(|tick| tick.is_newer_than(last_tick.0, ticks.this_run()));
+
+        if woken {
+            woken_entities.insert(entity);
+            if let Some(island) = island {
+                woken_islands.insert(island.0);
+            }
+        }
+    }
+
+    if woken_entities.is_empty() {
+        return;
+    }
+
+    for (entity, island, ..) in &bodies {
+        let in_woken_island = island.is_some_and(|island| woken_islands.contains(&island.0));
+        if woken_entities.contains(&entity) || in_woken_island {
+            commands.entity(entity).remove::<Sleeping>();
+            commands.entity(entity).insert(TimeSleeping(0.0));
+        }
     }
 }
 
+/// Records the tick of the physics step that just finished, so the next step's
+/// [`wake_on_external_change`] can tell which component changes are new.
+fn update_last_physics_tick(mut last_tick: ResMut<LastPhysicsTick>, ticks: SystemChangeTick) {
+    last_tick.0 = ticks.this_run();
+}
+
 fn gravity_deactivate_sleeping(
     mut commands: Commands,
     mut bodies: Query<(Entity, &mut TimeSleeping), With<Sleeping>>,
@@ -122,3 +661,157 @@ fn gravity_deactivate_sleeping(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn entity(index: u32) -> Entity {
+        Entity::from_raw(index)
+    }
+
+    #[test]
+    fn union_find_islands_merges_connected_bodies() {
+        let a = entity(0);
+        let b = entity(1);
+        let c = entity(2);
+
+        let islands = union_find_islands([a, b, c].into_iter(), [(a, b)].into_iter());
+
+        assert_eq!(islands[&a], islands[&b]);
+        assert_ne!(islands[&a], islands[&c]);
+    }
+
+    #[test]
+    fn union_find_islands_keeps_unconnected_bodies_separate() {
+        let a = entity(0);
+        let b = entity(1);
+
+        let islands = union_find_islands([a, b].into_iter(), std::iter::empty());
+
+        assert_ne!(islands[&a], islands[&b]);
+    }
+
+    #[test]
+    fn union_find_islands_ignores_edges_to_non_dynamic_bodies() {
+        let a = entity(0);
+        let b = entity(1);
+        // `b` is static or kinematic, so it never appears among the dynamic bodies, even
+        // though it's touched by an edge.
+        let islands = union_find_islands([a].into_iter(), [(a, b)].into_iter());
+
+        assert_eq!(islands.len(), 1);
+        assert_eq!(islands[&a], a);
+    }
+
+    #[test]
+    fn union_find_islands_splits_when_the_bridging_body_is_absent() {
+        // Two pairs bridged only through a third, non-dynamic body never merge.
+        let a = entity(0);
+        let b = entity(1);
+        let c = entity(2);
+        let d = entity(3);
+        let bridge = entity(4);
+
+        let islands = union_find_islands(
+            [a, b, c, d].into_iter(),
+            [(a, bridge), (bridge, b), (c, d)].into_iter(),
+        );
+
+        assert_ne!(islands[&a], islands[&b]);
+        assert_eq!(islands[&c], islands[&d]);
+        assert_ne!(islands[&a], islands[&c]);
+    }
+
+    #[test]
+    fn islands_past_deactivation_time_only_past_threshold() {
+        let awake = entity(0);
+        let asleep = entity(1);
+        let mut min_time_sleeping = HashMap::new();
+        min_time_sleeping.insert(awake, 0.5);
+        min_time_sleeping.insert(asleep, 2.0);
+
+        let sleeping = islands_past_deactivation_time(min_time_sleeping, 1.0, std::iter::empty());
+
+        assert!(!sleeping.contains(&awake));
+        assert!(sleeping.contains(&asleep));
+    }
+
+    #[test]
+    fn islands_past_deactivation_time_excludes_moving_sleeping_disabled_islands() {
+        let island = entity(0);
+        let mut min_time_sleeping = HashMap::new();
+        min_time_sleeping.insert(island, 2.0);
+
+        let sleeping =
+            islands_past_deactivation_time(min_time_sleeping, 1.0, std::iter::once(island));
+
+        assert!(sleeping.is_empty());
+    }
+
+    #[test]
+    fn interpolation_alpha_scales_with_accumulated_time() {
+        let mut fixed_time = FixedTime::new_from_secs(1.0);
+
+        assert_eq!(interpolation_alpha(&fixed_time), 0.0);
+
+        fixed_time.tick(Duration::from_secs_f32(0.5));
+        assert_eq!(interpolation_alpha(&fixed_time), 0.5);
+
+        fixed_time.tick(Duration::from_secs_f32(0.5));
+        assert_eq!(interpolation_alpha(&fixed_time), 1.0);
+    }
+
+    #[test]
+    fn interpolation_alpha_clamps_overshoot() {
+        let mut fixed_time = FixedTime::new_from_secs(1.0);
+        fixed_time.tick(Duration::from_secs_f32(5.0));
+
+        assert_eq!(interpolation_alpha(&fixed_time), 1.0);
+    }
+
+    #[test]
+    fn velocity_from_delta_divides_by_dt() {
+        assert_eq!(velocity_from_delta(4.0_f64, 2.0, false), 2.0);
+    }
+
+    #[test]
+    fn velocity_from_delta_is_zero_for_a_teleport() {
+        assert_eq!(velocity_from_delta(4.0_f64, 2.0, true), 0.0);
+    }
+
+    #[test]
+    fn velocity_from_delta_is_zero_for_a_non_positive_dt() {
+        assert_eq!(velocity_from_delta(4.0_f64, 0.0, false), 0.0);
+    }
+
+    #[cfg(feature = "3d")]
+    #[test]
+    fn angular_velocity_from_rotation_delta_is_zero_for_a_teleport() {
+        let delta = Quaternion::from_rotation_y(1.0);
+        assert_eq!(
+            angular_velocity_from_rotation_delta(delta, 1.0, true),
+            Vector::ZERO
+        );
+    }
+
+    #[cfg(feature = "3d")]
+    #[test]
+    fn angular_velocity_from_rotation_delta_is_zero_for_identity() {
+        assert_eq!(
+            angular_velocity_from_rotation_delta(Quaternion::IDENTITY, 1.0, false),
+            Vector::ZERO
+        );
+    }
+
+    #[test]
+    fn tick_is_newer_than_detects_changes_since_last_physics_tick() {
+        let last_tick = Tick::new(1);
+        let this_run = Tick::new(3);
+
+        assert!(Tick::new(2).is_newer_than(last_tick, this_run));
+        assert!(!Tick::new(1).is_newer_than(last_tick, this_run));
+    }
+}